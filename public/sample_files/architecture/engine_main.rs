@@ -1,59 +1,471 @@
 use pulsar_std::prelude::*;
+use std::collections::HashSet;
 
 /// Main engine initialization and game loop
 pub fn main() {
     // Initialize logging
     env_logger::init();
-    
+
     // Create the Pulsar app
     let mut app = PulsarApp::new();
-    
-    // Register custom types
-    app.register_type::<GameState>();
-    app.register_type::<Inventory>();
-    app.register_type::<PlayerData>();
-    
-    // Register systems
-    app.add_system(game_state_system);
-    app.add_system(inventory_system);
-    app.add_system(player_update_system);
-    
-    // Load initial scene
-    app.load_scene("scenes/default.level");
-    
+
+    // `DefaultPlugins` brings in the built-in engine functionality
+    // (rendering, input, time); the rest are game-specific plugins that
+    // each bundle their own types, resources, events and systems.
+    app.add_plugins((
+        DefaultPlugins,
+        GameStatePlugin,
+        PlayerPlugin,
+        CollisionPlugin,
+        InventoryPlugin,
+        DiagnosticsPlugin::default().with_overlay(true),
+        AnimationPlugin,
+    ));
+
     // Start the game loop
     app.run();
 }
 
-fn game_state_system(state: Res<GameState>) {
-    // Handle game state transitions
-    match *state {
-        GameState::MainMenu => {
-            // Display main menu UI
+struct GameStatePlugin;
+
+impl Plugin for GameStatePlugin {
+    fn build(&self, app: &mut PulsarApp) {
+        app.register_type::<GameState>();
+        app.add_state::<GameState>(GameState::MainMenu);
+        app.add_system_on_enter(GameState::MainMenu, show_main_menu);
+        app.add_system_in_state(GameState::MainMenu, start_game_on_input);
+        app.add_system_on_enter(GameState::Loading, start_loading_level);
+        app.add_system_in_state(GameState::Loading, check_level_loaded);
+        app.add_system_on_enter(GameState::Playing, spawn_level);
+        app.add_system_on_exit(GameState::Playing, cleanup_level);
+        app.add_system_on_enter(GameState::Paused, show_pause_menu);
+    }
+}
+
+struct PlayerPlugin;
+
+impl Plugin for PlayerPlugin {
+    fn build(&self, app: &mut PulsarApp) {
+        app.register_type::<PlayerData>();
+        app.add_event::<PlayerDamaged>();
+        app.add_system(damage_system);
+
+        // Movement is integrated on a fixed 60 Hz schedule so it stays
+        // deterministic regardless of render frame rate
+        app.set_fixed_timestep(1.0 / 60.0);
+        app.add_fixed_system(player_update_system);
+
+        // Renders from an interpolated transform so movement still looks
+        // smooth between fixed steps on faster/slower monitors
+        app.add_system(interpolate_player_transform);
+    }
+}
+
+struct CollisionPlugin;
+
+impl Plugin for CollisionPlugin {
+    fn build(&self, app: &mut PulsarApp) {
+        app.register_type::<Collider>();
+        app.register_type::<Hazard>();
+        app.add_event::<CollisionEvent>();
+        app.init_resource::<ActiveCollisions>();
+        app.add_fixed_system(collision_system);
+    }
+}
+
+struct InventoryPlugin;
+
+impl Plugin for InventoryPlugin {
+    fn build(&self, app: &mut PulsarApp) {
+        app.register_type::<Inventory>();
+        app.add_system_in_state(GameState::Playing, inventory_system);
+    }
+}
+
+struct DiagnosticsPlugin {
+    overlay: bool,
+}
+
+impl Default for DiagnosticsPlugin {
+    fn default() -> Self {
+        Self { overlay: false }
+    }
+}
+
+impl DiagnosticsPlugin {
+    fn with_overlay(mut self, enabled: bool) -> Self {
+        self.overlay = enabled;
+        self
+    }
+}
+
+impl Plugin for DiagnosticsPlugin {
+    fn build(&self, app: &mut PulsarApp) {
+        app.init_resource::<SystemStats>();
+        app.init_resource::<Diagnostics>();
+        app.add_system(diagnostics_system);
+
+        if self.overlay {
+            app.enable_diagnostics_overlay();
+        }
+    }
+}
+
+struct AnimationPlugin;
+
+impl Plugin for AnimationPlugin {
+    fn build(&self, app: &mut PulsarApp) {
+        app.register_type::<SpriteAnimation>();
+        app.add_event::<FootstepPlayed>();
+        app.add_system_in_state(GameState::Playing, select_animation_clip);
+        app.add_system_in_state(GameState::Playing, animate_sprites);
+    }
+}
+
+struct PlayerDamaged {
+    player: Entity,
+    amount: f32,
+}
+
+// Transform as of the previous fixed step, used to interpolate a smooth
+// RenderTransform between one fixed step and the next
+struct PreviousTransform(Transform);
+
+// What's actually drawn; lags Transform by up to one fixed step
+struct RenderTransform(Transform);
+
+// Tags entities spawned for the current level so cleanup_level can despawn them
+struct LevelRoot;
+
+struct Collider {
+    radius: f32,
+}
+
+struct Hazard;
+
+struct CollisionEvent {
+    a: Entity,
+    b: Entity,
+    kind: CollisionEventKind,
+}
+
+enum CollisionEventKind {
+    Began,
+    Ended,
+}
+
+// Pairs overlapping as of last fixed step; diffed against the current step
+// so Began/Ended fire once per contact instead of every tick of an overlap
+#[derive(Default)]
+struct ActiveCollisions(HashSet<(Entity, Entity)>);
+
+struct LevelAssets {
+    folder: Handle<LoadedFolder>,
+    player_texture: Handle<Texture>,
+}
+
+#[derive(Default)]
+struct Diagnostics {
+    frame_count: u64,
+    frame_time: DiagnosticMeasurement,
+    fps: DiagnosticMeasurement,
+    cpu_usage: DiagnosticMeasurement,
+    memory_usage: DiagnosticMeasurement,
+}
+
+// Ring buffer of the last HISTORY_LEN samples for one measurement
+struct DiagnosticMeasurement {
+    samples: [f64; Self::HISTORY_LEN],
+    next: usize,
+    len: usize,
+}
+
+impl DiagnosticMeasurement {
+    const HISTORY_LEN: usize = 120;
+
+    fn record(&mut self, sample: f64) {
+        self.samples[self.next] = sample;
+        self.next = (self.next + 1) % Self::HISTORY_LEN;
+        self.len = (self.len + 1).min(Self::HISTORY_LEN);
+    }
+
+    // Most recently recorded sample
+    fn value(&self) -> f64 {
+        if self.len == 0 {
+            return 0.0;
+        }
+        let last = (self.next + Self::HISTORY_LEN - 1) % Self::HISTORY_LEN;
+        self.samples[last]
+    }
+
+    fn average(&self) -> f64 {
+        if self.len == 0 {
+            return 0.0;
+        }
+        self.samples[..self.len].iter().sum::<f64>() / self.len as f64
+    }
+}
+
+impl Default for DiagnosticMeasurement {
+    fn default() -> Self {
+        Self {
+            samples: [0.0; Self::HISTORY_LEN],
+            next: 0,
+            len: 0,
+        }
+    }
+}
+
+#[derive(Default)]
+struct SystemStats {
+    backend: system_info::System,
+}
+
+struct SpriteAnimation {
+    first_index: usize,
+    last_index: usize,
+    fps: f32,
+    timer: Timer,
+    current_frame: usize,
+}
+
+impl SpriteAnimation {
+    fn new(first_index: usize, last_index: usize, fps: f32) -> Self {
+        Self {
+            first_index,
+            last_index,
+            fps,
+            timer: Timer::from_seconds(1.0 / fps, TimerMode::Repeating),
+            current_frame: first_index,
         }
-        GameState::Playing => {
-            // Update gameplay systems
+    }
+}
+
+const IDLE_CLIP: (usize, usize) = (0, 3);
+const IDLE_FPS: f32 = 4.0;
+const WALKING_CLIP: (usize, usize) = (4, 9);
+const WALKING_FPS: f32 = 10.0;
+// Frame in WALKING_CLIP where the boot touches the ground
+const FOOTSTEP_FRAME: usize = 6;
+
+struct FootstepPlayed {
+    entity: Entity,
+}
+
+fn show_main_menu() {
+    // Display main menu UI
+}
+
+fn start_game_on_input(input: Res<Input>, mut next_state: ResMut<NextState<GameState>>) {
+    // Leave the menu and start loading the level once the player presses start
+    if input.just_pressed(KeyCode::Return) {
+        next_state.set(GameState::Loading);
+    }
+}
+
+fn show_pause_menu() {
+    // Show pause menu
+}
+
+fn start_loading_level(asset_server: Res<AssetServer>, mut commands: Commands) {
+    let folder = asset_server.load_folder("scenes/default.level");
+    let player_texture = asset_server.load::<Texture>("textures/player.png");
+    commands.insert_resource(LevelAssets {
+        folder,
+        player_texture,
+    });
+}
+
+// Only moves on to GameState::Playing once every handle has resolved
+fn check_level_loaded(
+    assets: Res<LevelAssets>,
+    asset_server: Res<AssetServer>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    if asset_server.is_loaded(&assets.folder) && asset_server.is_loaded(&assets.player_texture) {
+        next_state.set(GameState::Playing);
+    }
+}
+
+fn spawn_level(mut commands: Commands) {
+    commands.spawn(LevelRoot);
+}
+
+fn cleanup_level(mut commands: Commands, query: Query<Entity, With<LevelRoot>>) {
+    for entity in query.iter() {
+        commands.despawn(entity);
+    }
+}
+
+// Naive O(n^2) broad+narrow phase; fine for the handful of colliders a level
+// has active at once
+fn collision_system(
+    colliders: Query<(Entity, &Transform, &Collider)>,
+    mut active: ResMut<ActiveCollisions>,
+    mut events: EventWriter<CollisionEvent>,
+) {
+    let colliders: Vec<_> = colliders.iter().collect();
+    let mut overlapping = HashSet::new();
+    for i in 0..colliders.len() {
+        for j in (i + 1)..colliders.len() {
+            let (a, transform_a, collider_a) = colliders[i];
+            let (b, transform_b, collider_b) = colliders[j];
+            let distance = transform_a.translation.distance(transform_b.translation);
+            if distance < collider_a.radius + collider_b.radius {
+                overlapping.insert(canonical_pair(a, b));
+            }
+        }
+    }
+
+    for &(a, b) in overlapping.difference(&active.0) {
+        events.send(CollisionEvent {
+            a,
+            b,
+            kind: CollisionEventKind::Began,
+        });
+    }
+    for &(a, b) in active.0.difference(&overlapping) {
+        events.send(CollisionEvent {
+            a,
+            b,
+            kind: CollisionEventKind::Ended,
+        });
+    }
+
+    active.0 = overlapping;
+}
+
+fn canonical_pair(a: Entity, b: Entity) -> (Entity, Entity) {
+    if a < b {
+        (a, b)
+    } else {
+        (b, a)
+    }
+}
+
+// Other collision pairs (e.g. player-vs-pickup) are left for their own systems
+fn damage_system(
+    mut collisions: EventReader<CollisionEvent>,
+    players: Query<&PlayerData>,
+    hazards: Query<&Hazard>,
+    mut damaged: EventWriter<PlayerDamaged>,
+) {
+    for event in collisions.read() {
+        if !matches!(event.kind, CollisionEventKind::Began) {
+            continue;
         }
-        GameState::Paused => {
-            // Show pause menu
+
+        let (player, other) = if players.get(event.a).is_ok() {
+            (event.a, event.b)
+        } else if players.get(event.b).is_ok() {
+            (event.b, event.a)
+        } else {
+            continue;
+        };
+
+        if hazards.get(other).is_ok() {
+            damaged.send(PlayerDamaged {
+                player,
+                amount: 10.0,
+            });
         }
-        _ => {}
     }
 }
 
-fn inventory_system(mut inventory: ResMut<Inventory>) {
+fn diagnostics_system(
+    time: Res<Time>,
+    mut stats: ResMut<SystemStats>,
+    mut diagnostics: ResMut<Diagnostics>,
+) {
+    let dt = time.delta_seconds() as f64;
+    diagnostics.frame_count += 1;
+    diagnostics.frame_time.record(dt);
+    diagnostics
+        .fps
+        .record(if dt > 0.0 { 1.0 / dt } else { 0.0 });
+
+    stats.backend.refresh();
+    diagnostics
+        .cpu_usage
+        .record(stats.backend.process_cpu_usage());
+    diagnostics
+        .memory_usage
+        .record(stats.backend.process_memory_bytes() as f64);
+}
+
+fn inventory_system(mut inventory: ResMut<Inventory>, mut damaged: EventReader<PlayerDamaged>) {
     // Update inventory UI and logic
     if inventory.items.len() > inventory.capacity {
         println!("Warning: Inventory is full!");
     }
+
+    // React to damage events without depending on whatever system caused them
+    for event in damaged.read() {
+        println!("Player {:?} took {} damage", event.player, event.amount);
+    }
 }
 
+// Runs on the fixed-update schedule, so delta_seconds() is always one fixed step
 fn player_update_system(
-    mut query: Query<(&mut Transform, &PlayerData)>,
-    time: Res<Time>,
+    mut query: Query<(&mut Transform, &mut PreviousTransform, &PlayerData)>,
+    time: Res<FixedTime>,
 ) {
-    for (mut transform, player) in query.iter_mut() {
+    for (mut transform, mut previous, player) in query.iter_mut() {
+        previous.0 = *transform;
         // Update player position, animation, etc.
         transform.translation.y += player.velocity.y * time.delta_seconds();
     }
 }
+
+// Runs every render frame, not just on fixed steps, so the drawn position
+// eases smoothly toward Transform using the fixed schedule's leftover fraction
+fn interpolate_player_transform(
+    fixed_time: Res<FixedTime>,
+    mut query: Query<(&Transform, &PreviousTransform, &mut RenderTransform)>,
+) {
+    for (transform, previous, mut render_transform) in query.iter_mut() {
+        render_transform.0 = previous.0.lerp(*transform, fixed_time.overstep_fraction());
+    }
+}
+
+// Replacing the whole component (rather than the range in place) restarts the
+// frame timer whenever the clip actually changes
+fn select_animation_clip(mut query: Query<(&PlayerData, &mut SpriteAnimation)>) {
+    for (player, mut animation) in query.iter_mut() {
+        let is_walking = player.velocity.length_squared() > 0.01;
+        let (first_index, last_index, fps) = if is_walking {
+            (WALKING_CLIP.0, WALKING_CLIP.1, WALKING_FPS)
+        } else {
+            (IDLE_CLIP.0, IDLE_CLIP.1, IDLE_FPS)
+        };
+
+        if animation.first_index != first_index {
+            *animation = SpriteAnimation::new(first_index, last_index, fps);
+        }
+    }
+}
+
+fn animate_sprites(
+    time: Res<Time>,
+    mut query: Query<(Entity, &mut SpriteAnimation, &mut TextureAtlasIndex)>,
+    mut footsteps: EventWriter<FootstepPlayed>,
+) {
+    for (entity, mut animation, mut atlas_index) in query.iter_mut() {
+        animation.timer.tick(time.delta_seconds());
+        if !animation.timer.just_finished() {
+            continue;
+        }
+
+        let next_frame = animation.current_frame + 1;
+        animation.current_frame = if next_frame > animation.last_index {
+            animation.first_index
+        } else {
+            next_frame
+        };
+        atlas_index.0 = animation.current_frame;
+
+        if animation.current_frame == FOOTSTEP_FRAME {
+            footsteps.send(FootstepPlayed { entity });
+        }
+    }
+}